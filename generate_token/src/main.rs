@@ -15,6 +15,7 @@ use regex::Regex;
 use serde_json::{json, Map, Value};
 use std::collections::HashMap;
 use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tower_http::cors::{Any, CorsLayer};
 use uuid::Uuid;
 use async_once::AsyncOnce;
@@ -39,7 +40,7 @@ lazy_static! {
     });
     static ref CORS_LAYER : AsyncOnce<CorsLayer> = AsyncOnce::new(async {
         let cors_layer = CorsLayer::new()
-        .allow_methods(vec![Method::GET, Method::POST])
+        .allow_methods(vec![Method::GET, Method::POST, Method::DELETE])
         .allow_origin(Any);
         cors_layer
     });
@@ -48,6 +49,251 @@ lazy_static! {
 }
 
 
+//An OAuth2-style scope in the `repository:action` form used by the
+//orca-registry token service (e.g. `repository:my-app:pull`). Scopes are
+//granted at issue time and enforced per-scope by downstream callers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Scope(String);
+
+impl Scope {
+    //parse a single whitespace/comma trimmed scope token, keeping only values
+    //that carry at least one `:` separated action segment
+    fn parse(raw: &str) -> Option<Scope> {
+        let trimmed = raw.trim();
+        if trimmed.is_empty() || !trimmed.contains(':') {
+            return None;
+        }
+        Some(Scope(trimmed.to_string()))
+    }
+
+    fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+//parse the requested scopes from the OAuth convention `scope` parameter,
+//accepting either whitespace or comma separated values
+fn parse_scopes(raw: &str) -> Vec<Scope> {
+    raw.split(|c: char| c.is_whitespace() || c == ',')
+        .filter_map(Scope::parse)
+        .collect()
+}
+
+//parse a caller-supplied `scope` value, rejecting the request when it carries
+//tokens but none are valid `repository:action` scopes — rather than silently
+//discarding them and falling back to the role default
+fn parse_requested_scopes(raw: &str) -> Result<Vec<Scope>, Diagnostic> {
+    let tokens: Vec<&str> = raw
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .map(|t| t.trim())
+        .filter(|t| !t.is_empty())
+        .collect();
+    let scopes = parse_scopes(raw);
+    if !tokens.is_empty() && scopes.is_empty() {
+        return Err(Diagnostic::BadIdentity(format!(
+            "no valid repository:action scopes in request: {}",
+            raw
+        )));
+    }
+    Ok(scopes)
+}
+
+//policy hook: when a request grants no scopes of its own, derive a default set
+//from the extracted role name so every token carries an enforceable baseline
+fn default_scopes_for_role(role_name: &str) -> Vec<Scope> {
+    vec![Scope(format!("role:{}:access", role_name))]
+}
+
+//The declared type of a Cognito custom (`custom:`) attribute, used to coerce
+//the flat string value into a typed DynamoDB `AttributeValue`.
+#[derive(Debug, Clone)]
+enum CustomAttributeType {
+    String,
+    Number,
+    Boolean,
+    DateTime,
+}
+
+//One entry of the configurable custom-attribute schema (name, type and
+//whether it is required), mirroring the Cognito custom-attributes feature.
+//Mutability is not part of the schema.
+#[derive(Debug, Clone)]
+struct CustomAttributeDef {
+    name: String,
+    attr_type: CustomAttributeType,
+    required: bool,
+}
+
+//load the custom-attribute schema from the `CUSTOM_ATTRIBUTE_SCHEMA` env var,
+//a JSON array of `{name, type, required}` objects. An unset or
+//malformed value yields an empty schema so behaviour is unchanged by default.
+fn load_custom_attribute_schema() -> Vec<CustomAttributeDef> {
+    let raw = env::var("CUSTOM_ATTRIBUTE_SCHEMA").unwrap_or_else(|_| "[]".to_string());
+    let parsed: Value = serde_json::from_str(&raw).unwrap_or(json!([]));
+    let mut defs = Vec::new();
+    if let Some(arr) = parsed.as_array() {
+        for entry in arr {
+            let name = match entry["name"].as_str() {
+                Some(n) => n.to_string(),
+                None => continue,
+            };
+            let attr_type = match entry["type"].as_str() {
+                Some("Number") => CustomAttributeType::Number,
+                Some("Boolean") => CustomAttributeType::Boolean,
+                Some("DateTime") => CustomAttributeType::DateTime,
+                _ => CustomAttributeType::String,
+            };
+            defs.push(CustomAttributeDef {
+                name,
+                attr_type,
+                required: entry["required"].as_bool().unwrap_or(false),
+            });
+        }
+    }
+    defs
+}
+
+//coerce the `custom:`-prefixed values from the normalized Cognito attributes
+//into typed DynamoDB `AttributeValue`s according to the schema. Returns a
+//`BadIdentity` diagnostic when a required attribute is absent or a value does
+//not parse as its declared type.
+fn build_custom_attributes(
+    user_attributes: &HashMap<String, String>,
+    schema: &[CustomAttributeDef],
+) -> Result<HashMap<String, AttributeValue>, Diagnostic> {
+    let mut custom = HashMap::new();
+    for def in schema {
+        let key = format!("custom:{}", def.name);
+        match user_attributes.get(key.as_str()) {
+            Some(value) => {
+                let av = match def.attr_type {
+                    //DateTime is persisted as an epoch-seconds number
+                    CustomAttributeType::Number | CustomAttributeType::DateTime => {
+                        value.parse::<f64>().map_err(|_| {
+                            Diagnostic::BadIdentity(format!(
+                                "custom attribute {} is not a number: {}",
+                                def.name, value
+                            ))
+                        })?;
+                        AttributeValue::N(value.to_string())
+                    }
+                    CustomAttributeType::Boolean => {
+                        let parsed = value.parse::<bool>().map_err(|_| {
+                            Diagnostic::BadIdentity(format!(
+                                "custom attribute {} is not a boolean: {}",
+                                def.name, value
+                            ))
+                        })?;
+                        AttributeValue::Bool(parsed)
+                    }
+                    CustomAttributeType::String => AttributeValue::S(value.to_string()),
+                };
+                custom.insert(def.name.clone(), av);
+            }
+            None if def.required => {
+                return Err(Diagnostic::BadIdentity(format!(
+                    "required custom attribute {} is missing from the cognito user",
+                    def.name
+                )));
+            }
+            None => {}
+        }
+    }
+    Ok(custom)
+}
+
+//Internal error type for the handler chain. Carries an `errorType` and
+//`errorMessage` and serializes like the Lambda runtime's `Diagnostic` record,
+//while also knowing which HTTP status each failure class maps to.
+#[derive(Debug)]
+enum Diagnostic {
+    //malformed ARN / provider string / identity input -> 400
+    BadIdentity(String),
+    //the Cognito user-pool lookup returned no matching user -> 404
+    UserNotFound(String),
+    //a downstream Cognito/DynamoDB/Lambda call failed -> 502
+    Upstream(String),
+}
+
+impl Diagnostic {
+    fn error_type(&self) -> &'static str {
+        match self {
+            Diagnostic::BadIdentity(_) => "BadIdentity",
+            Diagnostic::UserNotFound(_) => "UserNotFound",
+            Diagnostic::Upstream(_) => "UpstreamServiceError",
+        }
+    }
+
+    fn status(&self) -> u16 {
+        match self {
+            Diagnostic::BadIdentity(_) => 400,
+            Diagnostic::UserNotFound(_) => 404,
+            Diagnostic::Upstream(_) => 502,
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            Diagnostic::BadIdentity(m)
+            | Diagnostic::UserNotFound(m)
+            | Diagnostic::Upstream(m) => m,
+        }
+    }
+
+    //render the JSON error body returned to the caller
+    fn to_response(&self) -> Response<Body> {
+        let body = json!({
+            "errorType": self.error_type(),
+            "errorMessage": self.message(),
+        });
+        Response::builder()
+            .status(self.status())
+            .body(body.to_string().into())
+            .unwrap()
+    }
+}
+
+//The identity-pool login source a caller authenticated through, as enumerated
+//by the CreateIdentityPool API. Only the user-pool branch carries a Cognito
+//user to look up; every other branch is a federated or guest identity.
+enum IdentityProvider {
+    CognitoUserPool([String; 2]),
+    Saml(String),
+    OpenIdConnect(String),
+    DeveloperAuthenticated(String),
+    Unauthenticated,
+}
+
+//inspect the Cognito identity metadata and classify the login source. An
+//absent/`unauthenticated` auth type or a missing provider string is a guest
+//identity; otherwise the provider string tells us which federation it is.
+fn detect_provider(
+    identity_info: &aws_lambda_events::apigw::ApiGatewayRequestIdentity,
+) -> Result<IdentityProvider, Diagnostic> {
+    let auth_type = identity_info
+        .cognito_authentication_type
+        .clone()
+        .unwrap_or_default();
+    let provider = match identity_info.cognito_authentication_provider.clone() {
+        Some(p) => p,
+        None => return Ok(IdentityProvider::Unauthenticated),
+    };
+    if auth_type != "authenticated" {
+        return Ok(IdentityProvider::Unauthenticated);
+    }
+
+    if provider.contains("cognito-idp.") {
+        Ok(IdentityProvider::CognitoUserPool(extract_user_pool_info(provider)?))
+    } else if provider.contains("saml-provider/") {
+        Ok(IdentityProvider::Saml(provider))
+    } else if provider.contains("oidc-provider/") || provider.contains("://") {
+        Ok(IdentityProvider::OpenIdConnect(provider))
+    } else {
+        //a bare developer provider name (e.g. `login.mycompany.myapp`)
+        Ok(IdentityProvider::DeveloperAuthenticated(provider))
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     //anything inside main will be kept warm inbetween invocations
@@ -55,13 +301,61 @@ async fn main() -> Result<(), Error> {
     debug!("main started");
     let handler = ServiceBuilder::new()
         .layer(CORS_LAYER.get().await)
-        .service(service_fn(generate_token));
+        .service(service_fn(route_request));
     
     lambda_http::run(handler).await?;
     Ok(())
 }
 
 
+//dispatch incoming requests across the issue, verify and revoke handlers. A
+//DELETE or the `/revoke` path revokes a token (`revoke`); otherwise the
+//`/verify` path (or a GET) checks an existing one (`verify_token`), and any
+//other request mints a token (`generate_token`), all sharing the CORS stack above.
+async fn route_request(event: Request) -> Result<Response<Body>, Error> {
+    let path = event.uri().path().to_string();
+    if event.method() == Method::DELETE || path.ends_with("/revoke") {
+        revoke(event).await
+    } else if event.method() == Method::GET || path.ends_with("/verify") {
+        verify_token(event).await
+    } else {
+        generate_token(event).await
+    }
+}
+
+//read the bearer token from an Authorization header or a JSON `token` body
+//field; shared by the verify and revoke handlers
+fn token_from_request(event: &Request) -> String {
+    match event.headers().get("Authorization") {
+        Some(header) => header
+            .to_str()
+            .unwrap_or("")
+            .trim_start_matches("Bearer ")
+            .to_string(),
+        None => {
+            let body: Value = serde_json::from_slice(event.body()).unwrap_or(json!({}));
+            body["token"].as_str().unwrap_or("").to_string()
+        }
+    }
+}
+
+//revoke a token by flipping its `valid` flag; returns 200 on success and 401
+//when no token was supplied.
+async fn revoke(event: Request) -> Result<Response<Body>, Error> {
+    debug!("revoke running");
+    let token = token_from_request(&event);
+    if token.is_empty() {
+        return Ok(unauthorized());
+    }
+    if let Err(diagnostic) = revoke_token(&token).await {
+        return Ok(diagnostic.to_response());
+    }
+    Ok(Response::builder()
+        .status(200)
+        .body("Revoked".into())
+        .unwrap())
+}
+
 async fn generate_token(event: Request) -> Result<Response<Body>, Error> {
     debug!("generate token running");
     let ctx = event.request_context();
@@ -70,9 +364,26 @@ async fn generate_token(event: Request) -> Result<Response<Body>, Error> {
     match ctx {
         lambda_http::request::RequestContext::ApiGatewayV1(x) => {
             let identity_info = x.identity;
-            let token = generate_token_for_identity(identity_info).await?;
-            Ok(Response::builder().status(200).body(token.into()).unwrap())
-        },  
+            //collect the raw `scope` value from the query string or JSON body
+            let raw_scope = match event.query_string_parameters().first("scope") {
+                Some(scope) => Some(scope.to_string()),
+                None => {
+                    let body: Value = serde_json::from_slice(event.body()).unwrap_or(json!({}));
+                    body["scope"].as_str().map(|s| s.to_string())
+                }
+            };
+            let requested = match raw_scope {
+                Some(raw) => match parse_requested_scopes(&raw) {
+                    Ok(scopes) => scopes,
+                    Err(diagnostic) => return Ok(diagnostic.to_response()),
+                },
+                None => Vec::new(),
+            };
+            match generate_token_for_identity(identity_info, requested).await {
+                Ok(token) => Ok(Response::builder().status(200).body(token.into()).unwrap()),
+                Err(diagnostic) => Ok(diagnostic.to_response()),
+            }
+        },
         _ => Ok(Response::builder()
             .status(400)
             .body("Not an ApiGatewayV1 request".into())
@@ -80,85 +391,233 @@ async fn generate_token(event: Request) -> Result<Response<Body>, Error> {
     }
 }
 
+//validate a previously issued token and echo back its stored claims. Mirrors
+//the VerifyUserAccessToken operation: look the row up by `pk`, reject when it
+//is missing, expired, or has `valid == false`, otherwise return its claims.
+async fn verify_token(event: Request) -> Result<Response<Body>, Error> {
+    debug!("verify token running");
+    let client = DYNAMO_CLIENT.get().await;
+
+    //accept the token in an Authorization header or a JSON `token` body field
+    let token = token_from_request(&event);
+
+    if token.is_empty() {
+        return Ok(unauthorized());
+    }
+
+    let result = match client
+        .get_item()
+        // hard coded for now but just use env
+        .table_name("lpb-benchmark-corvus-auth-tokens-mcguire")
+        .key("pk", AttributeValue::S(token))
+        .send()
+        .await
+    {
+        Ok(result) => result,
+        Err(e) => {
+            return Ok(
+                Diagnostic::Upstream(format!("dynamodb get_item failed: {}", e)).to_response(),
+            )
+        }
+    };
+
+    let item = match result.item() {
+        Some(item) => item,
+        None => return Ok(unauthorized()),
+    };
+
+    //reject revoked tokens and anything past its expiry
+    let valid = matches!(item.get("valid"), Some(AttributeValue::Bool(true)));
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let expires_at = item
+        .get("expiresAt")
+        .and_then(|av| av.as_n().ok())
+        .and_then(|n| n.parse::<u64>().ok())
+        .unwrap_or(0);
+    if !valid || expires_at <= now {
+        return Ok(unauthorized());
+    }
+
+    let claims = json!({
+        "roleName": item.get("roleName").and_then(|av| av.as_s().ok()),
+        "userAttributes": attribute_value_to_json(item.get("userAttributes")),
+        "identityInfo": attribute_value_to_json(item.get("identityInfo")),
+        "scopes": item.get("scopes").and_then(|av| av.as_ss().ok()),
+    });
+    Ok(Response::builder()
+        .status(200)
+        .body(claims.to_string().into())
+        .unwrap())
+}
+
+fn unauthorized() -> Response<Body> {
+    Response::builder()
+        .status(401)
+        .body("Unauthorized".into())
+        .unwrap()
+}
+
+//conversion of a stored DynamoDB attribute back into JSON for the
+//verification response, recursing into maps; only strings, numbers (as JSON
+//strings), booleans and maps are handled, anything else becomes null.
+fn attribute_value_to_json(value: Option<&AttributeValue>) -> Value {
+    match value {
+        Some(AttributeValue::S(s)) => json!(s),
+        Some(AttributeValue::N(n)) => json!(n),
+        Some(AttributeValue::Bool(b)) => json!(b),
+        Some(AttributeValue::M(map)) => {
+            let mut obj = Map::new();
+            for (k, v) in map {
+                obj.insert(k.clone(), attribute_value_to_json(Some(v)));
+            }
+            Value::Object(obj)
+        }
+        _ => Value::Null,
+    }
+}
+
 async fn generate_token_for_identity(
     identity_info: aws_lambda_events::apigw::ApiGatewayRequestIdentity,
-) -> Result<String, Error> {
+    requested_scopes: Vec<Scope>,
+) -> Result<String, Diagnostic> {
     let client = COGNITO_CLIENT.get().await;
     debug!("Now generating token...");
 
     //get necessary auth data
     let uuid = Uuid::new_v4().to_string();
-    let role_name = extract_role_name(identity_info.user_arn.clone().unwrap());
+    let user_arn = identity_info
+        .user_arn
+        .clone()
+        .ok_or_else(|| Diagnostic::BadIdentity("identity is missing a user ARN".to_string()))?;
+    let role_name = extract_role_name(user_arn)?;
     let mut auth_data = Map::new();
-    let provider = identity_info.cognito_authentication_provider.clone().unwrap();
-    let user_pool_info = extract_user_pool_info(provider);
-    let user = get_user_attributes(&user_pool_info, client).await;
-    let user_attributes = normalize_cognito_user_attributes(&user);
+
+    //classify the login source and only hit Cognito's user-pool `list_users`
+    //when there is an actual user-pool user; federated/guest callers skip it.
+    let provider = detect_provider(&identity_info)?;
+    let mut user_attributes: HashMap<String, String> = HashMap::new();
+    auth_data.insert("user_pool_info".to_string(), json!(""));
+    match &provider {
+        IdentityProvider::CognitoUserPool(user_pool_info) => {
+            let user = get_user_attributes(user_pool_info, client).await?;
+            user_attributes = normalize_cognito_user_attributes(&user)?;
+            auth_data.insert("provider_type".to_string(), json!("cognito_user_pool"));
+            auth_data.insert("user_pool_info".to_string(), json!(user_pool_info));
+        }
+        IdentityProvider::Saml(arn) => {
+            auth_data.insert("provider_type".to_string(), json!("saml"));
+            auth_data.insert("saml_provider".to_string(), json!(arn));
+        }
+        IdentityProvider::OpenIdConnect(arn) => {
+            auth_data.insert("provider_type".to_string(), json!("oidc"));
+            auth_data.insert("oidc_provider".to_string(), json!(arn));
+        }
+        IdentityProvider::DeveloperAuthenticated(name) => {
+            auth_data.insert("provider_type".to_string(), json!("developer"));
+            auth_data.insert("developer_provider".to_string(), json!(name));
+        }
+        IdentityProvider::Unauthenticated => {
+            auth_data.insert("provider_type".to_string(), json!("unauthenticated"));
+            auth_data.insert(
+                "cognito_identity_id".to_string(),
+                json!(identity_info.cognito_identity_id),
+            );
+        }
+    }
+
+    //fall back to the role-derived default scope set when none were requested
+    let granted_scopes = if requested_scopes.is_empty() {
+        default_scopes_for_role(&role_name)
+    } else {
+        requested_scopes
+    };
+    let scope_strings: Vec<String> =
+        granted_scopes.iter().map(|s| s.as_str().to_string()).collect();
 
     //construct auth_data Map
     auth_data.insert("token".to_string(), json!(&uuid));
     auth_data.insert("identity_info".to_string(), json!(identity_info));
     auth_data.insert("role_name".to_string(), json!(role_name));
-    auth_data.insert(
-        "user_pool_info".to_string(),
-        json!(""
-        ),
-    );
+    auth_data.insert("scopes".to_string(), json!(scope_strings));
     auth_data.insert("user_attributes".to_string(), json!(user_attributes));
-    auth_data.insert("connection_type".to_string(), json!(""));
-    let auth_type = identity_info.cognito_authentication_type.clone().unwrap();
-    if auth_type == "authenticated".to_string() {
-        auth_data.insert("user_pool_info".to_string(), json!(user_pool_info));
-    }
     auth_data.insert(
         "connection_type".to_string(),
         json!(identity_info.cognito_authentication_type),
     );
-    let SHOULD_GET_APPLICATION_USER_PROFILE =
+    let should_get_application_user_profile =
         env::var("SHOULD_GET_APPLICATION_USER_PROFILE").unwrap_or_else(|_| "".to_string());
-    if auth_data.get("connection_type").unwrap() == &json!(&"authenticated")
-        && SHOULD_GET_APPLICATION_USER_PROFILE == "true"
+    if auth_data.get("connection_type") == Some(&json!("authenticated"))
+        && should_get_application_user_profile == "true"
     {
         let profile = invoke_serverless_core_event(
             String::from("coreGetApplicationUserProfile"),
-            &auth_data
-        );
+            &auth_data,
+        )
+        .await?;
+        auth_data.insert("application_user_profile".to_string(), profile);
     }
-    let SHOULD_BUILD_SECURE_CONNECTION_PARAMS =
+    let should_build_secure_connection_params =
         env::var("SHOULD_BUILD_SECURE_CONNECTION_PARAMS").unwrap_or_else(|_| "".to_string());
-    if SHOULD_BUILD_SECURE_CONNECTION_PARAMS == "true" {
+    if should_build_secure_connection_params == "true" {
         let secure_params = invoke_serverless_core_event(
             String::from("coreBuildSecureConnectionParams"),
-            &auth_data
-        );
+            &auth_data,
+        )
+        .await?;
+        auth_data.insert("secure_connection_params".to_string(), secure_params);
     }
-    store_token(&auth_data, &user_attributes).await?;
+    //coerce any declared custom attributes into typed values before persisting
+    let custom_schema = load_custom_attribute_schema();
+    let custom_attributes = build_custom_attributes(&user_attributes, &custom_schema)?;
+
+    store_token(&auth_data, &user_attributes, &custom_attributes)
+        .await
+        .map_err(|e| Diagnostic::Upstream(format!("failed to store token: {}", e)))?;
     Ok(uuid)
 }
 
 fn extract_role_name(
     user_arn: String
-) -> String {
+) -> Result<String, Diagnostic> {
     debug!("Extracting role name");
     let re = Regex::new(r"assumed-role/(.*)/").unwrap();
-    let found = re.find(&user_arn).unwrap().as_str();
+    let found = re
+        .find(&user_arn)
+        .ok_or_else(|| Diagnostic::BadIdentity(format!("no assumed-role in ARN: {}", user_arn)))?
+        .as_str();
     let split = found.split('/');
     let vec = split.collect::<Vec<&str>>().clone();
     debug!("role name extracted");
-    return vec[1].to_string();
+    Ok(vec[1].to_string())
 }
 
-fn extract_user_pool_info(auth_provider: String) -> [String; 2] {
+fn extract_user_pool_info(auth_provider: String) -> Result<[String; 2], Diagnostic> {
     debug!("extracting user pool info");
     let user_pool_re = Regex::new(r".{2}-.{4}-.{1}_.*,").unwrap();
     let user_pool_user_re = Regex::new(r":.*-.*-.*-.*-.*").unwrap();
-    let found_pool = user_pool_re.find(&auth_provider).unwrap().as_str();
-    let found_pool_user = user_pool_user_re.find(&auth_provider).unwrap().as_str();
+    let found_pool = user_pool_re
+        .find(&auth_provider)
+        .ok_or_else(|| {
+            Diagnostic::BadIdentity(format!("no user pool in provider: {}", auth_provider))
+        })?
+        .as_str();
+    let found_pool_user = user_pool_user_re
+        .find(&auth_provider)
+        .ok_or_else(|| {
+            Diagnostic::BadIdentity(format!("no user pool user in provider: {}", auth_provider))
+        })?
+        .as_str();
     debug!("Extracted user pool info");
-    return [found_pool[0..found_pool.len()-1].to_string(), found_pool_user[15..].to_string()];
+    Ok([found_pool[0..found_pool.len()-1].to_string(), found_pool_user[15..].to_string()])
 }
 
-async fn get_user_attributes(user_pool_info: &[String; 2], client: &CognitoClient) -> UserType {
+async fn get_user_attributes(
+    user_pool_info: &[String; 2],
+    client: &CognitoClient,
+) -> Result<UserType, Diagnostic> {
     debug!("Connecting to cognito...");
     let result = client
         .list_users()
@@ -167,47 +626,65 @@ async fn get_user_attributes(user_pool_info: &[String; 2], client: &CognitoClien
         .limit(1)
         .send()
         .await
-        .unwrap();
+        .map_err(|e| Diagnostic::Upstream(format!("cognito list_users failed: {}", e)))?;
 
-    let user = &result.users().unwrap()[0];
+    let users = result
+        .users()
+        .ok_or_else(|| Diagnostic::UserNotFound("no users returned by cognito".to_string()))?;
+    let user = users.first().ok_or_else(|| {
+        Diagnostic::UserNotFound(format!("no cognito user with sub {}", user_pool_info[1]))
+    })?;
     debug!("got stuff from cognito");
-    return user.clone();
+    Ok(user.clone())
 }
 
-fn normalize_cognito_user_attributes(user: &UserType) -> HashMap<&str, String> {
+fn normalize_cognito_user_attributes(user: &UserType) -> Result<HashMap<String, String>, Diagnostic> {
     debug!("normalizingg user attrs");
-    let attrs = user.attributes().unwrap();
+    let attrs = user
+        .attributes()
+        .ok_or_else(|| Diagnostic::Upstream("cognito user has no attributes".to_string()))?;
+    let user_create_date = user
+        .user_create_date()
+        .ok_or_else(|| Diagnostic::Upstream("cognito user has no create date".to_string()))?;
+    let user_last_modified_date = user
+        .user_last_modified_date()
+        .ok_or_else(|| Diagnostic::Upstream("cognito user has no last-modified date".to_string()))?;
+    let user_status = user
+        .user_status()
+        .ok_or_else(|| Diagnostic::Upstream("cognito user has no status".to_string()))?;
     let mut attributes_map = HashMap::from([
         (
-            "user_create_date",
-            user.user_create_date().unwrap().as_secs_f64().to_string(),
-        ),
-        (
-            "user_last_modified_date",
-            user.user_last_modified_date()
-                .unwrap()
-                .as_secs_f64()
-                .to_string(),
+            "user_create_date".to_string(),
+            user_create_date.as_secs_f64().to_string(),
         ),
-        ("enabled", user.enabled().to_string()),
         (
-            "user_status",
-            user.user_status().unwrap().as_str().to_string(),
+            "user_last_modified_date".to_string(),
+            user_last_modified_date.as_secs_f64().to_string(),
         ),
+        ("enabled".to_string(), user.enabled().to_string()),
+        ("user_status".to_string(), user_status.as_str().to_string()),
     ]);
     for attribute in attrs {
-        attributes_map.insert(
-            &attribute.name.as_ref().unwrap(),
-            attribute.value.as_ref().unwrap().to_string(),
-        );
+        let name = attribute.name.as_ref().ok_or_else(|| {
+            Diagnostic::Upstream("cognito attribute has no name".to_string())
+        })?;
+        //an attribute may legitimately carry a name with no value; treat the
+        //absent value as an empty string rather than panicking
+        let value = attribute
+            .value
+            .as_ref()
+            .map(|v| v.to_string())
+            .unwrap_or_default();
+        attributes_map.insert(name.to_string(), value);
     }
     debug!("Attributes: {:?}", attributes_map);
-    return attributes_map;
+    Ok(attributes_map)
 }
 
 async fn store_token(
     auth_data: &Map<String, Value>,
-    user_attributes: &HashMap<&str, String>,
+    user_attributes: &HashMap<String, String>,
+    custom_attributes: &HashMap<String, AttributeValue>,
 ) -> Result<(), Error> {
     debug!("starting to store token");
     let clone = auth_data.clone();
@@ -215,9 +692,17 @@ async fn store_token(
     let client =DYNAMO_CLIENT.get().await;
     debug!("finished getting client");
 
-    //construct attribute values
+    //construct attribute values; custom (`custom:`) attributes declared in the
+    //schema are coerced to typed values and nested separately, so keep them out
+    //of the flat map. Undeclared custom attributes stay flat as strings.
     let mut attrs = HashMap::new();
     for (name, value) in user_attributes.into_iter() {
+        let declared = name
+            .strip_prefix("custom:")
+            .map_or(false, |short| custom_attributes.contains_key(short));
+        if declared {
+            continue;
+        }
         attrs.insert(name.to_string(), AttributeValue::S(value.to_string()));
     }
     let identity_info = clone["identity_info"].as_object().unwrap();
@@ -233,8 +718,35 @@ async fn store_token(
         ("Attributes".to_string(),AttributeValue::M(attrs))
     ]);
     let attributes_av = AttributeValue::M(attributes);
+
+    //token lifecycle metadata (created/authType/valid/expiresAt) modeled on the
+    //access-token table used by the identity service, so issued tokens expire
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let ttl = env::var("TOKEN_TTL_SECONDS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(86400);
+    let expires_at = created + ttl;
+    let auth_type = clone["connection_type"]
+        .as_str()
+        .unwrap_or_else(|| "")
+        .to_string();
+
+    //granted scopes persist as a DynamoDB string-set so they round-trip intact
+    let scopes: Vec<String> = clone["scopes"]
+        .as_array()
+        .map(|arr| {
+            arr.iter()
+                .filter_map(|v| v.as_str().map(|s| s.to_string()))
+                .collect()
+        })
+        .unwrap_or_default();
+
     debug!("starting request");
-    let _request = client
+    let mut request = client
         .put_item()
         // hard coded for now but just use env
         .table_name("lpb-benchmark-corvus-auth-tokens-mcguire")
@@ -242,30 +754,97 @@ async fn store_token(
         .item("identityInfo", identity_info_av)
         .item("roleName", role_name_av)
         .item("userAttributes", attributes_av)
-        .send()
-        .await;
+        .item("created", AttributeValue::N(created.to_string()))
+        .item("authType", AttributeValue::S(auth_type))
+        .item("valid", AttributeValue::Bool(true))
+        // DynamoDB native TTL is enabled on `expiresAt` so rows auto-expire
+        .item("expiresAt", AttributeValue::N(expires_at.to_string()));
+    //a DynamoDB string-set may not be empty, so only attach it when populated
+    if !scopes.is_empty() {
+        request = request.item("scopes", AttributeValue::Ss(scopes));
+    }
+    //typed custom attributes live under their own map so callers can tell them
+    //apart from the flat standard attributes
+    if !custom_attributes.is_empty() {
+        request = request.item("customAttributes", AttributeValue::M(custom_attributes.clone()));
+    }
+    //persist the merged core-Lambda responses when they were fetched
+    if let Some(profile) = clone.get("application_user_profile") {
+        if !profile.is_null() {
+            request = request.item("applicationUserProfile", AttributeValue::S(profile.to_string()));
+        }
+    }
+    if let Some(params) = clone.get("secure_connection_params") {
+        if !params.is_null() {
+            request = request.item("secureConnectionParams", AttributeValue::S(params.to_string()));
+        }
+    }
+    request.send().await?;
     debug!("token stored");
     Ok(())
 }
 
+//flip a token's `valid` flag to false so it is rejected before TTL expiry
+async fn revoke_token(token: &str) -> Result<(), Diagnostic> {
+    debug!("revoking token");
+    let client = DYNAMO_CLIENT.get().await;
+    client
+        .update_item()
+        // hard coded for now but just use env
+        .table_name("lpb-benchmark-corvus-auth-tokens-mcguire")
+        .key("pk", AttributeValue::S(token.to_string()))
+        //`valid` is a DynamoDB reserved word, so reference it via a name alias
+        .update_expression("SET #v = :valid")
+        .expression_attribute_names("#v", "valid")
+        .expression_attribute_values(":valid", AttributeValue::Bool(false))
+        .send()
+        .await
+        .map_err(|e| Diagnostic::Upstream(format!("dynamodb update_item failed: {}", e)))?;
+    debug!("token revoked");
+    Ok(())
+}
+
+//invoke one of the serverless "core" Lambdas synchronously and hand back its
+//decoded JSON response. A populated `FunctionError` on the response is turned
+//into a structured `Upstream` diagnostic rather than being silently ignored.
 async fn invoke_serverless_core_event(
     event_name: String,
     payload: &Map<String, Value>,
-) -> Result<(), Error> {
+) -> Result<Value, Diagnostic> {
     let client = LambdaClient::new(CONFIG.get().await);
-    let name = format!(
-        "{:?}-{:?}-{:?}",
-        env::var("projectName"),
-        env::var("stage"),
-        event_name
-    );
-    let blob = serde_json::to_vec(&payload).unwrap();
+    //resolve the env values rather than debug-printing the `Result`s
+    let project_name = env::var("projectName").unwrap_or_default();
+    let stage = env::var("stage").unwrap_or_default();
+    let name = format!("{}-{}-{}", project_name, stage, event_name);
+    let blob = serde_json::to_vec(&payload)
+        .map_err(|e| Diagnostic::Upstream(format!("failed to serialize core payload: {}", e)))?;
     debug!("invoking event {:?} with payload {:?}", event_name, blob);
-    client
+    let response = client
         .invoke()
         .function_name(name)
-        .invocation_type(InvocationType::from("RequestResponse"))
-        .payload(Blob::new(blob));
-    Ok(())
+        .invocation_type(InvocationType::RequestResponse)
+        .payload(Blob::new(blob))
+        .send()
+        .await
+        .map_err(|e| Diagnostic::Upstream(format!("core invoke {} failed: {}", event_name, e)))?;
+
+    //a populated FunctionError means the core Lambda raised inside the handler
+    if let Some(function_error) = response.function_error() {
+        let detail = response
+            .payload()
+            .map(|b| String::from_utf8_lossy(b.as_ref()).to_string())
+            .unwrap_or_default();
+        return Err(Diagnostic::Upstream(format!(
+            "core event {} returned {}: {}",
+            event_name, function_error, detail
+        )));
+    }
+
+    //deserialize the returned payload blob into JSON for the caller to merge
+    match response.payload() {
+        Some(blob) => serde_json::from_slice(blob.as_ref())
+            .map_err(|e| Diagnostic::Upstream(format!("failed to parse core response: {}", e))),
+        None => Ok(Value::Null),
+    }
 }
 